@@ -16,7 +16,8 @@
 // limitations under the License.
 
 use sp_std::prelude::*;
-use crate::{weights::Weight, traits::Get, storage};
+use sp_std::collections::binary_heap::BinaryHeap;
+use crate::{weights::Weight, traits::Get, storage, dispatch::DispatchClass};
 use codec::{Encode, Decode};
 use sp_runtime::traits::Zero;
 use crate::{RuntimeDebugNoBound, PartialEqNoBound, EqNoBound, CloneNoBound};
@@ -36,7 +37,25 @@ const LOG_TARGET: &'static str = "runtime::task_executor";
 pub trait RuntimeTask:
 	Sized + Clone + Default + Encode + Decode + PartialEq + Eq + sp_std::fmt::Debug + codec::EncodeLike
 {
-	/// Execute the task while consuming self. The task must not most consume more than `max_weight`
+	/// Execute the task, consuming self, against a shared [`WeightMeter`].
+	///
+	/// Unlike [`Self::execute`], the task does not get a fixed `max_weight` handed to it in
+	/// isolation: it shares `meter` with whatever else is being executed in the same pass, so it
+	/// should [`WeightMeter::charge`] for weight as it actually uses it, and may
+	/// [`WeightMeter::refund`] back any part of an optimistic reservation that turned out to go
+	/// unused, making that weight available to the next task on the same meter.
+	///
+	/// `None` means that this task is now complete (and shall not be kept in storage anymore),
+	/// and `Some(_)` indicates that this task is not yet complete, and should be executed at a
+	/// later time.
+	///
+	/// It is critically important for a task to only charge the meter **IF it _actually did
+	/// something_**. If a positive charge is made without doing any work, an executor could
+	/// interpret this as a task that could use another execution slot, and continue the execution
+	/// potentially for numerous iterations.
+	fn execute_metered(self, meter: &mut WeightMeter) -> Option<Self>;
+
+	/// Execute the task while consuming self. The task must not consume more than `max_weight`
 	/// under any circumstance. Consuming *less* than `max_weight` is allowed.
 	///
 	/// A tuple is returned, where the items are as follows:
@@ -46,11 +65,14 @@ pub trait RuntimeTask:
 	///   2. The actual amount of weight that was consumed. Must always be less than `max_weight`.
 	///      parameter.
 	///
-	/// It is critically important for a task to only return a non-zero consumed weight **ONLY if it
-	/// _actually did something_**. If a positive weight is returned, then an executor could
-	/// interpret this as a task that could use another execution slot, and continue the execution
-	/// potentially for numerous iterations.
-	fn execute(self, max_weight: Weight) -> (Option<Self>, Weight);
+	/// This is a thin adapter around [`Self::execute_metered`] for callers that have no meter of
+	/// their own to share: it builds a one-shot [`WeightMeter`] out of `max_weight` and reports
+	/// back how much of it ended up charged.
+	fn execute(self, max_weight: Weight) -> (Option<Self>, Weight) {
+		let mut meter = WeightMeter::new(max_weight);
+		let maybe_next = self.execute_metered(&mut meter);
+		(maybe_next, meter.consumed())
+	}
 
 	/// The leftover weight that this task expects to execute, if any.
 	#[cfg(test)]
@@ -59,8 +81,8 @@ pub trait RuntimeTask:
 
 #[cfg(any(test, feature = "std"))]
 impl RuntimeTask for () {
-	fn execute(self, _: Weight) -> (Option<Self>, Weight) {
-		(None, 0)
+	fn execute_metered(self, _: &mut WeightMeter) -> Option<Self> {
+		None
 	}
 	#[cfg(test)]
 	fn leftover(&self) -> Weight {
@@ -100,6 +122,16 @@ pub trait StoredExecutor: codec::FullCodec {
 	/// Add a new task to the internal state.
 	fn add_task(&mut self, task: Self::Task);
 
+	/// Add a new task to the internal state, failing with the task itself if there is no more
+	/// room for it.
+	///
+	/// The default implementation defers to the infallible [`Self::add_task`], for executors
+	/// that, unlike [`BoundedExecutor`], have no notion of a maximum capacity.
+	fn try_add_task(&mut self, task: Self::Task) -> Result<(), Self::Task> {
+		self.add_task(task);
+		Ok(())
+	}
+
 	/// Remove all tasks, without executing any of them.
 	fn clear(&mut self);
 
@@ -235,10 +267,363 @@ macro_rules! impl_append_decode_len_shim {
 			}
 		}
 	};
+	// Bounded variant: same `Compact<u32>`-prefixed length as the unbounded one (that's how
+	// `BoundedVec` encodes too).
+	//
+	// FIXME(l0r1s/substrate#chunk0-2): this deliberately does NOT implement `StorageAppend`, so
+	// bounded executors lose the O(1) low-level append optimisation entirely and must go through
+	// a full `mutate` + `try_add_task` instead. The original ask was for `StorageAppend` itself to
+	// refuse to append past `Cap`, but `StorageAppend` is a marker-only, sealed trait: the type
+	// implementing it has no hook to run a check before the low-level bytes get written, so
+	// implementing it here would silently let a caller push the stored queue past `Cap` and
+	// corrupt the `decode_len` invariant it exists to protect. This is a scope cut from what was
+	// asked for, not a drop-in equivalent -- flagging for maintainer sign-off rather than quietly
+	// resolving it. If the O(1) append path is needed, it likely wants a purpose-built
+	// `unhashed`-level helper that checks `decode_len` before writing, rather than going through
+	// `StorageAppend`.
+	($executor:ident, bounded) => {
+		impl<Task, Quota, Cap> storage::private::Sealed for $executor<Task, Quota, Cap>
+		where
+			Task: RuntimeTask,
+			Quota: Get<Weight>,
+			Cap: Get<u32>,
+		{}
+		impl<Task, Quota, Cap> storage::StorageDecodeLength for $executor<Task, Quota, Cap>
+		where
+			Task: RuntimeTask,
+			Quota: Get<Weight>,
+			Cap: Get<u32>,
+		{}
+		impl<Task, Quota, Cap> codec::DecodeLength for $executor<Task, Quota, Cap>
+		where
+			Task: RuntimeTask,
+			Quota: Get<Weight>,
+			Cap: Get<u32>,
+		{
+			fn len(mut self_encoded: &[u8]) -> Result<usize, codec::Error> {
+				use sp_std::convert::TryFrom;
+				usize::try_from(u32::from(codec::Compact::<u32>::decode(&mut self_encoded)?))
+					.map_err(|_| "Failed convert decoded size into usize.".into())
+			}
+		}
+	};
+	// Same as the unbounded variant, but for executors whose `Task` additionally requires `Ord`
+	// (e.g. [`PriorityExecutor`]).
+	($executor:ident, ord) => {
+		impl<Task, Quota> storage::private::Sealed for $executor<Task, Quota>
+		where
+			Task: RuntimeTask + Ord,
+			Quota: Get<Weight>,
+		{}
+		impl<Task, Quota> storage::StorageAppend<Task> for $executor<Task, Quota>
+		where
+			Task: RuntimeTask + Ord,
+			Quota: Get<Weight>,
+		{}
+		impl<Task, Quota> storage::StorageDecodeLength for $executor<Task, Quota>
+		where
+			Task: RuntimeTask + Ord,
+			Quota: Get<Weight>,
+		{}
+		impl<Task, Quota> codec::DecodeLength for $executor<Task, Quota>
+		where
+			Task: RuntimeTask + Ord,
+			Quota: Get<Weight>,
+		{
+			fn len(mut self_encoded: &[u8]) -> Result<usize, codec::Error> {
+				use sp_std::convert::TryFrom;
+				usize::try_from(u32::from(codec::Compact::<u32>::decode(&mut self_encoded)?))
+					.map_err(|_| "Failed convert decoded size into usize.".into())
+			}
+		}
+	};
 }
 
 impl_append_decode_len_shim!(SinglePassExecutor);
 
+/// An executor backed by a fixed-capacity queue, in the spirit of a fixed-size pool.
+///
+/// Unlike [`SinglePassExecutor`], whose `Vec<Task>` can grow without limit as tasks are added,
+/// this variant is backed by a [`crate::BoundedVec`] capped at `Cap`, giving chains a hard bound
+/// on the per-block task-queue state and therefore a predictable PoV size.
+///
+/// FIXME(l0r1s/substrate#chunk0-2): unlike [`SinglePassExecutor`] and co, this type does NOT get
+/// the low-level `StorageAppend` optimisation -- see the `bounded` arm of
+/// `impl_append_decode_len_shim!` below for why, and flag with a maintainer before relying on an
+/// O(1) append path for this executor.
+#[derive(Encode, Decode, RuntimeDebugNoBound, PartialEqNoBound, EqNoBound, CloneNoBound)]
+pub struct BoundedExecutor<Task: RuntimeTask, Quota: Get<Weight> = (), Cap: Get<u32> = ()> {
+	/// The queue of tasks, capped at `Cap` entries.
+	pub(crate) tasks: crate::BoundedVec<Task, Cap>,
+	_marker: sp_std::marker::PhantomData<Quota>,
+}
+
+impl<Task: RuntimeTask, Quota: Get<Weight>, Cap: Get<u32>> Default
+	for BoundedExecutor<Task, Quota, Cap>
+{
+	fn default() -> Self {
+		Self { tasks: Default::default(), _marker: sp_std::marker::PhantomData }
+	}
+}
+
+impl<Task: RuntimeTask, Quota: Get<Weight>, Cap: Get<u32>> BoundedExecutor<Task, Quota, Cap> {
+	/// Add a new task to the queue, failing with the task itself if the queue is already at
+	/// `Cap`.
+	///
+	/// Note that this shadows [`StoredExecutor::add_task`], whose infallible signature forces it
+	/// to silently drop the task instead; callers should prefer this method, or
+	/// [`StoredExecutor::try_add_task`], whenever capacity matters.
+	pub fn add_task(&mut self, task: Task) -> Result<(), Task> {
+		self.tasks.try_push(task)
+	}
+}
+
+impl<Task: RuntimeTask, Quota: Get<Weight>, Cap: Get<u32>> StoredExecutor
+	for BoundedExecutor<Task, Quota, Cap>
+{
+	type Task = Task;
+	type Quota = Quota;
+
+	fn new() -> Self {
+		Default::default()
+	}
+
+	fn add_task(&mut self, task: Task) {
+		// best-effort: silently dropped once `Cap` is reached. Use `try_add_task` to observe
+		// the failure instead.
+		let _ = self.tasks.try_push(task);
+	}
+
+	fn try_add_task(&mut self, task: Task) -> Result<(), Task> {
+		self.tasks.try_push(task)
+	}
+
+	fn clear(&mut self) {
+		self.tasks.clear()
+	}
+
+	fn remove(&mut self, task: Task) {
+		let maybe_index = self.tasks.iter().position(|t| t == &task);
+		if let Some(index) = maybe_index {
+			self.tasks.remove(index);
+		}
+	}
+
+	fn count(&self) -> usize {
+		self.tasks.len()
+	}
+
+	#[cfg(any(test, feature = "std"))]
+	fn tasks(&self) -> Vec<Task> {
+		self.tasks.to_vec()
+	}
+
+	fn execute(&mut self) -> Weight {
+		let max_weight = Self::Quota::get();
+		let (next_tasks, consumed) = single_pass::<Task>(self.tasks.as_ref(), max_weight);
+		// `next_tasks` can never exceed `Cap`, since a single pass only ever removes tasks.
+		self.tasks = crate::BoundedVec::try_from(next_tasks)
+			.expect("single_pass only removes tasks, so the result still fits in Cap; qed");
+		consumed
+	}
+}
+
+impl_append_decode_len_shim!(BoundedExecutor, bounded);
+
+/// An executor that keeps re-running [`single_pass`] over its queue until a fixpoint is reached.
+///
+/// [`SinglePassExecutor`] can leave weight on the table: a non-greedy or "half" task further down
+/// the queue might become satisfiable only once an earlier task has shrunk or vanished, but a
+/// single pass never goes back to retry it. This variant instead loops, feeding the leftover
+/// tasks and leftover weight from one pass into the next, until the quota is exhausted, a whole
+/// pass makes no further progress, or `tasks.len() + 1` passes have been made.
+#[derive(Encode, Decode, RuntimeDebugNoBound, PartialEqNoBound, EqNoBound, CloneNoBound)]
+pub struct MultiPassExecutor<Task: RuntimeTask, Quota: Get<Weight> = ()> {
+	/// The queue of tasks.
+	pub(crate) tasks: Vec<Task>,
+	_marker: sp_std::marker::PhantomData<Quota>,
+}
+
+impl<Task: RuntimeTask, Quota: Get<Weight>> Default for MultiPassExecutor<Task, Quota> {
+	fn default() -> Self {
+		Self { tasks: vec![], _marker: sp_std::marker::PhantomData }
+	}
+}
+
+impl<Task: RuntimeTask, Quota: Get<Weight>> StoredExecutor for MultiPassExecutor<Task, Quota> {
+	type Task = Task;
+	type Quota = Quota;
+
+	fn new() -> Self {
+		Self { tasks: vec![], _marker: Default::default() }
+	}
+
+	fn add_task(&mut self, task: Task) {
+		self.tasks.push(task)
+	}
+
+	fn clear(&mut self) {
+		self.tasks.clear()
+	}
+
+	fn remove(&mut self, task: Task) {
+		let maybe_index = self.tasks.iter().position(|t| t == &task);
+		if let Some(index) = maybe_index {
+			self.tasks.remove(index);
+		}
+	}
+
+	fn count(&self) -> usize {
+		self.tasks.len()
+	}
+
+	#[cfg(any(test, feature = "std"))]
+	fn tasks(&self) -> Vec<Task> {
+		self.tasks.clone()
+	}
+
+	fn execute(&mut self) -> Weight {
+		let mut remaining = Self::Quota::get();
+		let mut tasks = sp_std::mem::take(&mut self.tasks);
+		let mut total_consumed = Weight::zero();
+
+		// FIXME(l0r1s/substrate#chunk0-3): cap the number of passes at the queue length. Without
+		// this, a task that reports a tiny but non-zero charge on every call defeats all three
+		// break conditions below simultaneously (quota isn't exhausted, `consumed` isn't zero, and
+		// the queue keeps shrinking by one), so the number of -- each O(n) -- passes is bounded
+		// only by `remaining / smallest-possible-charge` rather than by `tasks.len()`, which makes
+		// the wall-clock cost of `execute` unbounded relative to the weight it reports. `len() + 1`
+		// is the most passes a queue of this size could ever need to fully drain one task per pass.
+		let max_passes = tasks.len().saturating_add(1);
+
+		for _ in 0..max_passes {
+			if remaining.is_zero() {
+				break;
+			}
+
+			let prior_tasks = tasks.clone();
+			let (next_tasks, consumed) = single_pass::<Task>(&tasks, remaining);
+			total_consumed = total_consumed.saturating_add(consumed);
+			remaining = remaining.saturating_sub(consumed);
+			tasks = next_tasks;
+
+			// Stop once a pass makes no progress at all, or -- belt and braces, in case a task
+			// reports non-zero progress without actually shrinking the queue -- once the queue
+			// comes out of a pass unchanged from what went in.
+			if consumed.is_zero() || tasks == prior_tasks {
+				break;
+			}
+		}
+
+		self.tasks = tasks;
+		total_consumed
+	}
+}
+
+impl_append_decode_len_shim!(MultiPassExecutor);
+
+/// An executor that drains its queue in priority order, via a binary-heap, rather than FIFO.
+///
+/// `Task: Ord` defines priority: the greatest task according to its `Ord` impl is always executed
+/// next. This lets runtimes express urgent maintenance work -- migrations, slashing, and the like
+/// -- that should pre-empt lower-priority queued tasks within the same weight budget.
+///
+/// Tasks are stored as a plain `Vec` (so storage encoding and the [`impl_append_decode_len_shim`]
+/// machinery stay the same as [`SinglePassExecutor`]); heap order is only an invariant maintained
+/// while [`Self::execute`] is running, not part of the encoding.
+#[derive(Encode, Decode, RuntimeDebugNoBound, PartialEqNoBound, EqNoBound, CloneNoBound)]
+pub struct PriorityExecutor<Task: RuntimeTask + Ord, Quota: Get<Weight> = ()> {
+	/// The queue of tasks.
+	pub(crate) tasks: Vec<Task>,
+	_marker: sp_std::marker::PhantomData<Quota>,
+}
+
+impl<Task: RuntimeTask + Ord, Quota: Get<Weight>> Default for PriorityExecutor<Task, Quota> {
+	fn default() -> Self {
+		Self { tasks: vec![], _marker: sp_std::marker::PhantomData }
+	}
+}
+
+impl<Task: RuntimeTask + Ord, Quota: Get<Weight>> StoredExecutor for PriorityExecutor<Task, Quota> {
+	type Task = Task;
+	type Quota = Quota;
+
+	fn new() -> Self {
+		Self { tasks: vec![], _marker: Default::default() }
+	}
+
+	fn add_task(&mut self, task: Task) {
+		self.tasks.push(task)
+	}
+
+	fn clear(&mut self) {
+		self.tasks.clear()
+	}
+
+	fn remove(&mut self, task: Task) {
+		let maybe_index = self.tasks.iter().position(|t| t == &task);
+		if let Some(index) = maybe_index {
+			self.tasks.remove(index);
+		}
+	}
+
+	fn count(&self) -> usize {
+		self.tasks.len()
+	}
+
+	#[cfg(any(test, feature = "std"))]
+	fn tasks(&self) -> Vec<Task> {
+		// expose tasks in priority order (highest first), for predictability.
+		BinaryHeap::from(self.tasks.clone()).into_sorted_vec().into_iter().rev().collect()
+	}
+
+	fn execute(&mut self) -> Weight {
+		let mut heap = self.tasks.drain(..).collect::<BinaryHeap<_>>();
+		let mut meter = WeightMeter::new(Self::Quota::get());
+
+		// FIXME(l0r1s/substrate#chunk0-5): cap total pops at the queue length. Without this, a
+		// task that keeps reporting itself unfinished while charging only a tiny amount each call
+		// defeats `made_progress` (it's never false) and `meter.remaining().is_zero()` is the only
+		// thing left to stop the loop, so iteration count is bounded by `remaining /
+		// smallest-possible-charge` rather than by how many tasks are actually queued. Bounding by
+		// `heap.len()` keeps the wall-clock cost of `execute` proportional to queue size; a task
+		// that needs more pops than that to fully drain just carries its leftover into next block,
+		// same as it would if the weight budget alone had run out.
+		let max_iterations = heap.len();
+
+		for _ in 0..max_iterations {
+			let task = match heap.pop() {
+				Some(task) => task,
+				None => break,
+			};
+
+			if meter.remaining().is_zero() {
+				heap.push(task);
+				break;
+			}
+
+			let before = meter.consumed();
+			let maybe_leftover = task.execute_metered(&mut meter);
+			let made_progress = meter.consumed() > before;
+
+			if let Some(leftover) = maybe_leftover {
+				heap.push(leftover);
+				if !made_progress {
+					// the highest-priority task made no progress and is still the top of the
+					// heap; popping it again would only spend the remaining iterations without
+					// progress, so stop.
+					break;
+				}
+			}
+		}
+
+		self.tasks = heap.into_vec();
+		meter.consumed()
+	}
+}
+
+impl_append_decode_len_shim!(PriorityExecutor, ord);
+
 /// Aggregator trait to indicate an executor with task `Task` has `decode_len` and `append`.
 pub trait StorageValueShim<Task: RuntimeTask>:
 	codec::DecodeLength
@@ -255,6 +640,60 @@ impl<Task, S> StorageValueShim<Task> for S where
 	Task: RuntimeTask
 {}
 
+/// The error returned by [`WeightMeter::charge`] when the meter does not have enough remaining
+/// weight to satisfy the requested charge.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Exhausted;
+
+/// A gas-meter-style running tally of how much weight is left to spend.
+///
+/// The meter starts out at some fixed `limit` and is driven down by [`charge`](Self::charge) as
+/// work happens. Unlike handing out a bare `max_weight` to each task in turn, a shared meter lets
+/// a task that optimistically reserved more weight than it ended up needing
+/// [`refund`](Self::refund) the difference, so that the next task sharing the same meter can make
+/// use of it.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct WeightMeter {
+	limit: Weight,
+	consumed: Weight,
+}
+
+impl WeightMeter {
+	/// Create a new meter with `limit` weight available to spend.
+	pub fn new(limit: Weight) -> Self {
+		Self { limit, consumed: Zero::zero() }
+	}
+
+	/// Charge `weight` against the meter.
+	///
+	/// Fails with [`Exhausted`] and leaves the meter untouched if `weight` is more than
+	/// [`Self::remaining`].
+	pub fn charge(&mut self, weight: Weight) -> Result<(), Exhausted> {
+		let consumed = self.consumed.saturating_add(weight);
+		if consumed > self.limit {
+			return Err(Exhausted);
+		}
+		self.consumed = consumed;
+		Ok(())
+	}
+
+	/// Give back `weight` that was previously [`charge`](Self::charge)d but, in hindsight, not
+	/// actually used.
+	pub fn refund(&mut self, weight: Weight) {
+		self.consumed = self.consumed.saturating_sub(weight);
+	}
+
+	/// How much weight is still available to spend.
+	pub fn remaining(&self) -> Weight {
+		self.limit.saturating_sub(self.consumed)
+	}
+
+	/// How much weight has been charged so far, net of refunds.
+	pub fn consumed(&self) -> Weight {
+		self.consumed
+	}
+}
+
 /// Make a single pass over some tasks, returning a new set of tasks that remain un-finished, along
 /// the consumed weight.
 ///
@@ -265,18 +704,18 @@ pub(crate) fn single_pass<T: RuntimeTask>(tasks: &[T], max_weight: Weight) -> (V
 		return (tasks.to_vec(), Zero::zero());
 	}
 
-	let mut leftover_weight = max_weight;
+	// a single meter shared across the whole pass, so that a task refunding an over-estimated
+	// reservation makes that weight available to the next task in the same pass.
+	let mut meter = WeightMeter::new(max_weight);
 	let next_tasks = tasks
 		.iter()
 		.cloned()
 		.filter_map(|task| {
-			if leftover_weight.is_zero() {
+			if meter.remaining().is_zero() {
 				return Some(task);
 			}
 
-			let (maybe_leftover_task, consumed) = task.execute(leftover_weight);
-			leftover_weight = leftover_weight.saturating_sub(consumed);
-			maybe_leftover_task
+			task.execute_metered(&mut meter)
 		})
 		.collect::<Vec<_>>();
 
@@ -287,7 +726,181 @@ pub(crate) fn single_pass<T: RuntimeTask>(tasks: &[T], max_weight: Weight) -> (V
 		next_tasks,
 	);
 
-	(next_tasks, max_weight.saturating_sub(leftover_weight))
+	(next_tasks, meter.consumed())
+}
+
+/// A container holding one value of `T` per [`DispatchClass`], mirroring the shape of
+/// `PerDispatchClass` used for tracking per-class block weight limits.
+#[derive(Encode, Decode, RuntimeDebugNoBound, PartialEqNoBound, EqNoBound, CloneNoBound)]
+pub struct PerClass<T> {
+	/// Value for [`DispatchClass::Normal`].
+	pub normal: T,
+	/// Value for [`DispatchClass::Operational`].
+	pub operational: T,
+	/// Value for [`DispatchClass::Mandatory`].
+	pub mandatory: T,
+}
+
+// Implemented manually; this is the same impl that `#[derive(Default)]` would generate, with the
+// `T: Default` bound on this impl only, not on the struct itself.
+impl<T: Default> Default for PerClass<T> {
+	fn default() -> Self {
+		Self { normal: Default::default(), operational: Default::default(), mandatory: Default::default() }
+	}
+}
+
+impl<T> PerClass<T> {
+	/// Get a reference to the value stored for `class`.
+	pub fn get(&self, class: DispatchClass) -> &T {
+		match class {
+			DispatchClass::Normal => &self.normal,
+			DispatchClass::Operational => &self.operational,
+			DispatchClass::Mandatory => &self.mandatory,
+		}
+	}
+
+	/// Get a mutable reference to the value stored for `class`.
+	pub fn get_mut(&mut self, class: DispatchClass) -> &mut T {
+		match class {
+			DispatchClass::Normal => &mut self.normal,
+			DispatchClass::Operational => &mut self.operational,
+			DispatchClass::Mandatory => &mut self.mandatory,
+		}
+	}
+}
+
+/// The priority order in which [`ClassedExecutor`] drains its per-class queues: mandatory work
+/// always goes first, then operational, then normal.
+const CLASS_PRIORITY: [DispatchClass; 3] =
+	[DispatchClass::Mandatory, DispatchClass::Operational, DispatchClass::Normal];
+
+/// An executor that partitions its task queue by [`DispatchClass`] and enforces a weight budget
+/// per class, in addition to a combined ceiling across all classes.
+///
+/// This mirrors the way `BlockWeights` accounts for `Mandatory`/`Operational`/`Normal` separately
+/// while forbidding their sum from exceeding `MaxBlock`. Classes are drained in priority order --
+/// `Mandatory` first, then `Operational`, then `Normal` -- and a class stops consuming as soon as
+/// the shared `MaxBlock` ceiling runs out, even if its own `MaxTotal` budget still has headroom.
+///
+/// `Reserved` is an optional, per-class allotment that is allowed to dip into weight that would
+/// otherwise count against `MaxBlock`, i.e. a class may overrun the shared ceiling by up to its
+/// own reserved amount. This is analogous to the `reserved` field of `WeightsPerClass`.
+#[derive(Encode, Decode, RuntimeDebugNoBound, PartialEqNoBound, EqNoBound, CloneNoBound)]
+pub struct ClassedExecutor<Task: RuntimeTask, MaxTotal, MaxBlock, Reserved = ()>
+where
+	MaxTotal: Get<PerClass<Weight>>,
+	MaxBlock: Get<Weight>,
+	Reserved: Get<PerClass<Weight>>,
+{
+	/// The per-class queues of tasks.
+	pub(crate) tasks: PerClass<Vec<Task>>,
+	_marker: sp_std::marker::PhantomData<(MaxTotal, MaxBlock, Reserved)>,
+}
+
+impl<Task, MaxTotal, MaxBlock, Reserved> Default for ClassedExecutor<Task, MaxTotal, MaxBlock, Reserved>
+where
+	Task: RuntimeTask,
+	MaxTotal: Get<PerClass<Weight>>,
+	MaxBlock: Get<Weight>,
+	Reserved: Get<PerClass<Weight>>,
+{
+	fn default() -> Self {
+		Self { tasks: Default::default(), _marker: sp_std::marker::PhantomData }
+	}
+}
+
+impl<Task, MaxTotal, MaxBlock, Reserved> ClassedExecutor<Task, MaxTotal, MaxBlock, Reserved>
+where
+	Task: RuntimeTask,
+	MaxTotal: Get<PerClass<Weight>>,
+	MaxBlock: Get<Weight>,
+	Reserved: Get<PerClass<Weight>>,
+{
+	/// Add a new task to the queue of the given `class`.
+	///
+	/// Note that this shadows [`StoredExecutor::add_task`], which has no notion of dispatch
+	/// class and always falls back to [`DispatchClass::Normal`].
+	pub fn add_task(&mut self, class: DispatchClass, task: Task) {
+		self.tasks.get_mut(class).push(task);
+	}
+}
+
+impl<Task, MaxTotal, MaxBlock, Reserved> StoredExecutor for ClassedExecutor<Task, MaxTotal, MaxBlock, Reserved>
+where
+	Task: RuntimeTask,
+	MaxTotal: Get<PerClass<Weight>>,
+	MaxBlock: Get<Weight>,
+	Reserved: Get<PerClass<Weight>>,
+{
+	type Task = Task;
+	type Quota = MaxBlock;
+
+	fn new() -> Self {
+		Default::default()
+	}
+
+	fn add_task(&mut self, task: Task) {
+		self.tasks.get_mut(DispatchClass::Normal).push(task);
+	}
+
+	fn clear(&mut self) {
+		self.tasks.normal.clear();
+		self.tasks.operational.clear();
+		self.tasks.mandatory.clear();
+	}
+
+	fn remove(&mut self, task: Task) {
+		for class in CLASS_PRIORITY {
+			let queue = self.tasks.get_mut(class);
+			if let Some(index) = queue.iter().position(|t| t == &task) {
+				queue.remove(index);
+				return;
+			}
+		}
+	}
+
+	fn count(&self) -> usize {
+		self.tasks.normal.len() + self.tasks.operational.len() + self.tasks.mandatory.len()
+	}
+
+	#[cfg(any(test, feature = "std"))]
+	fn tasks(&self) -> Vec<Task> {
+		CLASS_PRIORITY
+			.iter()
+			.flat_map(|class| self.tasks.get(*class).clone())
+			.collect::<Vec<_>>()
+	}
+
+	fn execute(&mut self) -> Weight {
+		let max_total = MaxTotal::get();
+		let reserved = Reserved::get();
+		let mut remaining_block = MaxBlock::get();
+		let mut consumed_total = Weight::zero();
+
+		for class in CLASS_PRIORITY {
+			let class_budget = *max_total.get(class);
+			let class_reserved = *reserved.get(class);
+			// The class may spend up to its own budget, bounded by whatever is left of the
+			// shared block ceiling, plus whatever this class has reserved for itself.
+			let available =
+				sp_std::cmp::min(class_budget, remaining_block.saturating_add(class_reserved));
+			if available.is_zero() {
+				continue;
+			}
+
+			let queue = self.tasks.get_mut(class);
+			let (next_tasks, consumed) = single_pass::<Task>(queue.as_ref(), available);
+			*queue = next_tasks;
+
+			consumed_total = consumed_total.saturating_add(consumed);
+			// Only the portion of `consumed` beyond the class' own reserved amount counts
+			// against the shared ceiling.
+			remaining_block =
+				remaining_block.saturating_sub(consumed.saturating_sub(class_reserved));
+		}
+
+		consumed_total
+	}
 }
 
 #[cfg(test)]
@@ -383,20 +996,22 @@ mod tests {
 	}
 
 	impl RuntimeTask for Task {
-		fn execute(mut self, max_weight: Weight) -> (Option<Self>, Weight) {
+		fn execute_metered(mut self, meter: &mut WeightMeter) -> Option<Self> {
 			let weight_needed = self.weight;
-			match self.half {
+			let (maybe_leftover, consumed) = match self.half {
 				0 => {
 					// at this point we try and consume as much as possible.
-					self.consume(weight_needed, max_weight)
+					self.consume(weight_needed, meter.remaining())
 				}
 				_ => {
 					// try and consume either half of your needed weight, or all of the available,
 					// if it is less.
 					self.half -= 1;
-					self.consume(weight_needed / 2, max_weight)
+					self.consume(weight_needed / 2, meter.remaining())
 				}
-			}
+			};
+			meter.charge(consumed).expect("task never consumes more than `meter.remaining()`; qed");
+			maybe_leftover
 		}
 
 		fn leftover(&self) -> Weight {
@@ -410,6 +1025,55 @@ mod tests {
 		executor.tasks().iter().map(|t| t.leftover()).collect::<Vec<_>>()
 	}
 
+	/// A task that optimistically charges `reserve` weight upfront, then refunds whatever part of
+	/// it turned out to be unused, exercising the [`WeightMeter::refund`] path directly.
+	#[derive(Clone, Encode, Decode, Default, PartialEq, Eq, Debug)]
+	struct RefundingTask {
+		/// The amount of weight optimistically reserved before doing the work.
+		reserve: Weight,
+		/// The amount of weight actually needed; the rest of `reserve` is refunded.
+		actual_use: Weight,
+	}
+
+	impl RuntimeTask for RefundingTask {
+		fn execute_metered(self, meter: &mut WeightMeter) -> Option<Self> {
+			meter.charge(self.reserve).expect("test task always fits in quota");
+			meter.refund(self.reserve.saturating_sub(self.actual_use));
+			None
+		}
+
+		fn leftover(&self) -> Weight {
+			0
+		}
+	}
+
+	/// A task carrying an explicit priority, used to exercise [`PriorityExecutor`]'s heap-based
+	/// ordering. Behaves like an always-greedy [`Task`] of `weight`, otherwise.
+	#[derive(Clone, Encode, Decode, Default, PartialEq, Eq, Debug, PartialOrd, Ord)]
+	struct PriorityTask {
+		/// The highest `priority` is always executed first.
+		priority: u32,
+		/// How much weight this task still needs.
+		weight: Weight,
+	}
+
+	impl RuntimeTask for PriorityTask {
+		fn execute_metered(mut self, meter: &mut WeightMeter) -> Option<Self> {
+			let consumed = sp_std::cmp::min(self.weight, meter.remaining());
+			meter.charge(consumed).expect("consumed <= meter.remaining() by construction; qed");
+			self.weight -= consumed;
+			if self.weight > 0 {
+				Some(self)
+			} else {
+				None
+			}
+		}
+
+		fn leftover(&self) -> Weight {
+			self.weight
+		}
+	}
+
 	#[test]
 	fn shim_works() {
 		macro_rules! shim_test {
@@ -530,6 +1194,39 @@ mod tests {
 		assert_eq!(remaining_weights_of(&executor), vec![15, 10, 5]);
 	}
 
+	#[test]
+	fn weight_meter_charges_and_refunds() {
+		let mut meter = WeightMeter::new(10);
+		assert_eq!(meter.remaining(), 10);
+		assert_eq!(meter.consumed(), 0);
+
+		assert_eq!(meter.charge(4), Ok(()));
+		assert_eq!(meter.remaining(), 6);
+		assert_eq!(meter.consumed(), 4);
+
+		// not enough left; the meter is unaffected by a failed charge.
+		assert_eq!(meter.charge(10), Err(Exhausted));
+		assert_eq!(meter.remaining(), 6);
+
+		meter.refund(2);
+		assert_eq!(meter.remaining(), 8);
+		assert_eq!(meter.consumed(), 2);
+	}
+
+	#[test]
+	fn single_pass_shares_refunded_weight_across_tasks() {
+		// the first task optimistically reserves 8 out of a 10 quota, but only actually needs 2,
+		// refunding 6 back into the shared meter; the second task, reserving 8 of its own, can
+		// then still fit within the 10 quota only because of that refund.
+		Quota::set(10);
+		let mut executor = SinglePassExecutor::<RefundingTask, Quota>::new();
+		executor.add_task(RefundingTask { reserve: 8, actual_use: 2 });
+		executor.add_task(RefundingTask { reserve: 8, actual_use: 8 });
+
+		assert_eq!(executor.execute(), 10);
+		assert_eq!(executor.count(), 0);
+	}
+
 	#[test]
 	fn empty_executor_is_noop() {
 		fn with_executor<E: StoredExecutor<Task = Task>>(mut executor: E) {
@@ -564,4 +1261,174 @@ mod tests {
 
 		with_executor(SinglePassExecutor::<Task, Quota>::new());
 	}
+
+	crate::parameter_types! {
+		static MaxTotal: PerClass<Weight> = PerClass { normal: 10, operational: 10, mandatory: 10 };
+		static MaxBlock: Weight = 15;
+		static Reserved: PerClass<Weight> = PerClass::default();
+	}
+
+	#[test]
+	fn classed_executor_drains_mandatory_before_others() {
+		MaxTotal::set(PerClass { normal: 10, operational: 10, mandatory: 10 });
+		MaxBlock::set(15);
+		Reserved::set(PerClass::default());
+
+		let mut executor =
+			ClassedExecutor::<Task, MaxTotal, MaxBlock, Reserved>::new();
+		executor.add_task(DispatchClass::Normal, TaskBuilder::default().build(10));
+		executor.add_task(DispatchClass::Mandatory, TaskBuilder::default().build(10));
+		executor.add_task(DispatchClass::Operational, TaskBuilder::default().build(10));
+		assert_eq!(executor.count(), 3);
+
+		// mandatory (10) + operational (5, capped by the remaining block budget) are drained
+		// first; normal never gets a chance this pass. Mandatory's task is fully consumed (and
+		// thus gone), leaving operational's partially-consumed task and normal's untouched one.
+		assert_eq!(executor.execute(), 15);
+		let remaining = executor.tasks();
+		assert_eq!(remaining.iter().map(|t| t.leftover()).collect::<Vec<_>>(), vec![5, 10]);
+	}
+
+	#[test]
+	fn classed_executor_respects_per_class_budget() {
+		MaxTotal::set(PerClass { normal: 10, operational: 2, mandatory: 10 });
+		MaxBlock::set(100);
+		Reserved::set(PerClass::default());
+
+		let mut executor =
+			ClassedExecutor::<Task, MaxTotal, MaxBlock, Reserved>::new();
+		executor.add_task(DispatchClass::Operational, TaskBuilder::default().build(10));
+
+		// even though the block ceiling has ample room, the class' own budget of 2 caps it.
+		assert_eq!(executor.execute(), 2);
+	}
+
+	#[test]
+	fn classed_executor_reserved_can_overrun_ceiling() {
+		MaxTotal::set(PerClass { normal: 0, operational: 0, mandatory: 10 });
+		MaxBlock::set(0);
+		Reserved::set(PerClass { normal: 0, operational: 0, mandatory: 10 });
+
+		let mut executor =
+			ClassedExecutor::<Task, MaxTotal, MaxBlock, Reserved>::new();
+		executor.add_task(DispatchClass::Mandatory, TaskBuilder::default().build(10));
+
+		// the block ceiling is fully exhausted (0), but mandatory's reserved allotment still
+		// lets it make progress.
+		assert_eq!(executor.execute(), 10);
+	}
+
+	crate::parameter_types! {
+		static Cap: u32 = 2;
+	}
+
+	#[test]
+	fn bounded_executor_rejects_tasks_past_cap() {
+		Cap::set(2);
+		Quota::set(100);
+		let mut executor = BoundedExecutor::<Task, Quota, Cap>::new();
+
+		assert_eq!(executor.add_task(TaskBuilder::default().build(10)), Ok(()));
+		assert_eq!(executor.add_task(TaskBuilder::default().build(10)), Ok(()));
+		assert_eq!(executor.count(), 2);
+
+		let rejected = TaskBuilder::default().build(10);
+		assert_eq!(executor.add_task(rejected.clone()), Err(rejected));
+		assert_eq!(executor.count(), 2);
+	}
+
+	#[test]
+	fn bounded_executor_try_add_task_matches_add_task() {
+		Cap::set(1);
+		Quota::set(100);
+		let mut executor = BoundedExecutor::<Task, Quota, Cap>::new();
+
+		assert_eq!(
+			StoredExecutor::try_add_task(&mut executor, TaskBuilder::default().build(10)),
+			Ok(())
+		);
+		let rejected = TaskBuilder::default().build(10);
+		assert_eq!(StoredExecutor::try_add_task(&mut executor, rejected.clone()), Err(rejected));
+	}
+
+	#[test]
+	fn multi_pass_executor_squeezes_out_leftover_weight() {
+		// same setup as `where_additional_pass_is_useful`, where a single pass leaves 6 weight
+		// unused even though the last task could have consumed 5 of it.
+		let mut executor = MultiPassExecutor::<Task, Quota>::new();
+		executor.add_task(TaskBuilder::default().half(1).greedy(false).build(30));
+		executor.add_task(TaskBuilder::default().half(1).greedy(false).build(20));
+		executor.add_task(TaskBuilder::default().half(1).greedy(false).build(10));
+
+		Quota::set(36);
+		// first pass: 15 + 10 + 5 = 30 consumed, 6 leftover. Second pass: the last task is no
+		// longer "half", so it can now consume the remaining 5 of its 5 leftover weight.
+		assert_eq!(executor.execute(), 35);
+		assert_eq!(remaining_weights_of(&executor), vec![15, 10]);
+	}
+
+	#[test]
+	fn multi_pass_executor_stops_once_quota_is_exhausted() {
+		let mut executor = MultiPassExecutor::<Task, Quota>::new();
+		executor.add_task(TaskBuilder::default().greedy(true).build(100));
+
+		Quota::set(5);
+		// the task is greedy and needs more than the quota, so it consumes the quota and stops;
+		// looping again would not help since there is no more weight to give it.
+		assert_eq!(executor.execute(), 5);
+		assert_eq!(remaining_weights_of(&executor), vec![95]);
+	}
+
+	#[test]
+	fn multi_pass_executor_matches_single_pass_for_homogenous_tasks() {
+		let mut executor = MultiPassExecutor::<Task, Quota>::new();
+		executor.add_task(TaskBuilder::default().build(10));
+		executor.add_task(TaskBuilder::default().build(10));
+		executor.add_task(TaskBuilder::default().build(10));
+
+		Quota::set(12);
+		assert_eq!(executor.execute(), 12);
+		assert_eq!(remaining_weights_of(&executor), vec![8, 10]);
+	}
+
+	#[test]
+	fn priority_executor_drains_highest_priority_first() {
+		Quota::set(5);
+		let mut executor = PriorityExecutor::<PriorityTask, Quota>::new();
+		executor.add_task(PriorityTask { priority: 1, weight: 10 });
+		executor.add_task(PriorityTask { priority: 3, weight: 10 });
+		executor.add_task(PriorityTask { priority: 2, weight: 10 });
+
+		assert_eq!(executor.execute(), 5);
+		let remaining = executor.tasks();
+		assert_eq!(remaining.iter().map(|t| t.priority).collect::<Vec<_>>(), vec![3, 2, 1]);
+		// the only 5 weight available this pass went to the highest-priority task.
+		assert_eq!(remaining[0].weight, 5);
+		assert_eq!(remaining[1].weight, 10);
+		assert_eq!(remaining[2].weight, 10);
+	}
+
+	#[test]
+	fn priority_executor_stops_immediately_without_quota() {
+		Quota::set(0);
+		let mut executor = PriorityExecutor::<PriorityTask, Quota>::new();
+		executor.add_task(PriorityTask { priority: 5, weight: 10 });
+		executor.add_task(PriorityTask { priority: 1, weight: 10 });
+
+		// no quota at all means the meter is empty from the start; we stop before executing even
+		// the top task, and both tasks are kept.
+		assert_eq!(executor.execute(), 0);
+		assert_eq!(executor.count(), 2);
+	}
+
+	#[test]
+	fn priority_executor_drains_in_full_given_enough_quota() {
+		Quota::set(100);
+		let mut executor = PriorityExecutor::<PriorityTask, Quota>::new();
+		executor.add_task(PriorityTask { priority: 1, weight: 10 });
+		executor.add_task(PriorityTask { priority: 2, weight: 20 });
+
+		assert_eq!(executor.execute(), 30);
+		assert_eq!(executor.count(), 0);
+	}
 }